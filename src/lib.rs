@@ -1,16 +1,203 @@
 use anyhow::{anyhow, bail, ensure, Result};
+use fs2::FileExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use rustc_version::Channel;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
 use std::env;
 use std::fs;
-use std::io;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
 use std::str;
 use walkdir::WalkDir;
 
-// FIXME: Hold some sort of lock while doing operations on our custom sysroot,
-// like cargo-xbuild does.
+/// A cfg-style predicate, parsed the way cargo-platform parses `cfg(...)`
+/// target predicates: identifiers combined with `all`, `any`, and `not`.
+#[derive(Debug, Clone)]
+enum CfgExpr {
+    Ident(String),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+impl CfgExpr {
+    /// The crate this expression blocks: its first identifier, in
+    /// left-to-right, depth-first order. E.g. in `all(alloc, test)` that's
+    /// `alloc` -- the remaining identifiers are read as a guard condition.
+    fn subject(&self) -> &str {
+        match self {
+            CfgExpr::Ident(name) => name,
+            CfgExpr::All(exprs) | CfgExpr::Any(exprs) => exprs[0].subject(),
+            CfgExpr::Not(expr) => expr.subject(),
+        }
+    }
+
+    /// Evaluate the expression against the set of crates already blocked by
+    /// a plain (non-predicate) `--block`, e.g. `all(alloc, test)` reads as
+    /// "block `alloc`, but only when `test` is also being blocked".
+    fn holds(&self, subject: &str, blocked: &HashSet<String>) -> bool {
+        // The subject carries no guard information of its own -- it's the
+        // thing being conditionally blocked, not a condition -- so it must
+        // be excluded before combining, not treated as an unconditionally
+        // true leaf. Otherwise `any(alloc, test)` would short-circuit true
+        // on the `alloc` leaf alone and never actually consult `test`.
+        self.eval(subject, blocked).unwrap_or(true)
+    }
+
+    fn eval(&self, subject: &str, blocked: &HashSet<String>) -> Option<bool> {
+        match self {
+            CfgExpr::Ident(name) if name == subject => None,
+            CfgExpr::Ident(name) => Some(blocked.contains(name)),
+            CfgExpr::All(exprs) => combine(exprs, subject, blocked, |vals| vals.iter().all(|&b| b)),
+            CfgExpr::Any(exprs) => combine(exprs, subject, blocked, |vals| vals.iter().any(|&b| b)),
+            CfgExpr::Not(expr) => expr.eval(subject, blocked).map(|b| !b),
+        }
+    }
+}
+
+/// Evaluate `exprs` with the subject filtered out, then combine the
+/// remaining (guard) results with `reduce`. Vacuous (no guard besides the
+/// subject, e.g. `all(alloc)`) is left unresolved for the caller to default.
+fn combine(
+    exprs: &[CfgExpr],
+    subject: &str,
+    blocked: &HashSet<String>,
+    reduce: impl Fn(&[bool]) -> bool,
+) -> Option<bool> {
+    let vals: Vec<bool> = exprs
+        .iter()
+        .filter_map(|e| e.eval(subject, blocked))
+        .collect();
+    if vals.is_empty() {
+        None
+    } else {
+        Some(reduce(&vals))
+    }
+}
+
+/// Crate names are blocked using their on-disk lib name (e.g. `libstd`), but
+/// it reads more naturally to let users pass `--block std`. Accept either.
+fn normalize_lib_name(name: &str) -> String {
+    if name.starts_with("lib") {
+        name.to_owned()
+    } else {
+        format!("lib{}", name)
+    }
+}
+
+fn parse_cfg_expr(input: &str) -> Result<CfgExpr> {
+    fn tokenize(input: &str) -> Vec<String> {
+        let mut tokens = Vec::new();
+        let mut current = String::new();
+        for c in input.chars() {
+            match c {
+                '(' | ')' | ',' => {
+                    if !current.trim().is_empty() {
+                        tokens.push(current.trim().to_owned());
+                    }
+                    current.clear();
+                    tokens.push(c.to_string());
+                }
+                _ => current.push(c),
+            }
+        }
+        if !current.trim().is_empty() {
+            tokens.push(current.trim().to_owned());
+        }
+        tokens
+    }
+
+    fn parse_expr(tokens: &[String], pos: &mut usize) -> Result<CfgExpr> {
+        let ident = tokens
+            .get(*pos)
+            .ok_or_else(|| anyhow!("unexpected end of `--block` predicate"))?
+            .clone();
+        *pos += 1;
+
+        if tokens.get(*pos).map(|s| s.as_str()) != Some("(") {
+            return Ok(CfgExpr::Ident(normalize_lib_name(&ident)));
+        }
+        *pos += 1;
+
+        let mut parts = Vec::new();
+        loop {
+            parts.push(parse_expr(tokens, pos)?);
+            match tokens.get(*pos).map(|s| s.as_str()) {
+                Some(",") => *pos += 1,
+                Some(")") => {
+                    *pos += 1;
+                    break;
+                }
+                _ => bail!("expected `,` or `)` in `--block` predicate `{}`", input),
+            }
+        }
+
+        match ident.as_str() {
+            "all" => Ok(CfgExpr::All(parts)),
+            "any" => Ok(CfgExpr::Any(parts)),
+            "not" => {
+                ensure!(parts.len() == 1, "`not(..)` takes exactly one argument");
+                Ok(CfgExpr::Not(Box::new(parts.into_iter().next().unwrap())))
+            }
+            other => bail!("unknown `--block` predicate function `{}`", other),
+        }
+    }
+
+    let tokens = tokenize(input);
+    let mut pos = 0;
+    let expr = parse_expr(&tokens, &mut pos)?;
+    ensure!(
+        pos == tokens.len(),
+        "trailing tokens in `--block` predicate `{}`",
+        input
+    );
+    Ok(expr)
+}
+
+/// The set of lib names to exclude from the generated `#![no_std]` sysroot.
+struct BlockList {
+    names: HashSet<String>,
+}
+
+impl BlockList {
+    fn from_args(args: &[String]) -> Result<BlockList> {
+        let mut explicit = HashSet::new();
+        explicit.insert("libstd".to_owned());
+
+        if has_flag("--core-only", args) {
+            explicit.insert("liballoc".to_owned());
+            explicit.insert("libtest".to_owned());
+            explicit.insert("libproc_macro".to_owned());
+        }
+
+        let mut predicates = Vec::new();
+        for raw in get_args_equals("--block", args) {
+            match parse_cfg_expr(&raw)? {
+                CfgExpr::Ident(name) => {
+                    explicit.insert(name);
+                }
+                expr => predicates.push(expr),
+            }
+        }
+
+        let mut names = explicit.clone();
+        for expr in &predicates {
+            let subject = expr.subject().to_owned();
+            if expr.holds(&subject, &explicit) {
+                names.insert(subject);
+            }
+        }
+
+        Ok(BlockList { names })
+    }
+
+    fn blocks(&self, lib_name: &str) -> bool {
+        self.names.contains(lib_name)
+    }
+}
 
 trait CommandExt {
     fn capture_stdout(&mut self) -> Result<Output>;
@@ -41,14 +228,35 @@ fn get_sysroot() -> Result<PathBuf> {
     Ok(stdout.into())
 }
 
-fn get_target_spec_json() -> Result<String> {
+fn get_target_spec_json(target: &str) -> Result<String> {
     let output = rustc()
-        .args(&["-Z", "unstable-options", "--print", "target-spec-json"])
+        .args(&[
+            "-Z",
+            "unstable-options",
+            "--target",
+            target,
+            "--print",
+            "target-spec-json",
+        ])
         .capture_stdout()?;
     ensure!(output.status.success(), "failed to get target spec json");
     Ok(String::from_utf8(output.stdout)?)
 }
 
+/// Check that the `rust-std` component for `target` is installed in
+/// `src_sysroot`, so we have pre-built rlibs to source the `#![no_std]`
+/// sysroot from.
+fn ensure_target_installed(src_sysroot: &Path, target: &str) -> Result<()> {
+    let lib_dir = src_sysroot.join("lib/rustlib").join(target).join("lib");
+    ensure!(
+        lib_dir.is_dir(),
+        "the `rust-std` component for target `{target}` is not installed in sysroot {}; \
+         run `rustup target add {target}` and try again",
+        src_sysroot.display(),
+    );
+    Ok(())
+}
+
 fn get_arg_equals(arg_name: &str, args: &[String]) -> Option<String> {
     let mut args_iter = args.iter();
     while let Some(arg) = args_iter.next() {
@@ -66,6 +274,137 @@ fn manifest_path_arg(args: &[String]) -> Option<String> {
     get_arg_equals("--manifest-path", args)
 }
 
+fn has_flag(arg_name: &str, args: &[String]) -> bool {
+    args.iter().any(|arg| arg == arg_name)
+}
+
+/// Like `get_arg_equals`, but `arg_name` may be repeated and every
+/// occurrence is collected, e.g. for `--block foo --block bar`.
+fn get_args_equals(arg_name: &str, args: &[String]) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        if arg == arg_name {
+            if let Some(val) = args_iter.next() {
+                result.push(val.clone());
+            }
+        } else if arg.starts_with(arg_name) && arg[arg_name.len()..].starts_with('=') {
+            result.push(arg[arg_name.len() + 1..].to_owned());
+        }
+    }
+    result
+}
+
+/// Drop `arg_name` (and its value, for the space-separated form) from `args`.
+/// Used to strip the user's real `--target` before we inject our own, fake
+/// one for cargo to build against, and to strip our own custom flags before
+/// forwarding the rest to `cargo build`.
+fn without_arg_equals(arg_name: &str, args: &[String]) -> Vec<String> {
+    let mut out = Vec::with_capacity(args.len());
+    let mut iter = args.iter().cloned();
+    while let Some(arg) = iter.next() {
+        if arg == arg_name {
+            iter.next();
+            continue;
+        }
+        if arg.starts_with(arg_name) && arg[arg_name.len()..].starts_with('=') {
+            continue;
+        }
+        out.push(arg);
+    }
+    out
+}
+
+/// Drop a bare flag (no value) like `--core-only` from `args`.
+fn without_flag(arg_name: &str, args: &[String]) -> Vec<String> {
+    args.iter().filter(|arg| *arg != arg_name).cloned().collect()
+}
+
+/// Does `pkg` have a `lib`, `rlib` or `proc-macro` target? Targets of any
+/// other kind (bins, examples, and also `dylib`/`cdylib`/`staticlib`
+/// libraries) don't count, since `#![no_std]` checking is scoped to those
+/// library crates here.
+fn has_lib_target(pkg: &cargo_metadata::Package) -> bool {
+    pkg.targets
+        .iter()
+        .any(|target| target.kind.iter().any(|kind| kind == "lib" || kind == "rlib" || kind == "proc-macro"))
+}
+
+/// Narrow `requested_kinds` down to the ones `pkg` actually has a target
+/// for, e.g. a package with no `[[bin]]` drops `--bins` rather than being
+/// passed straight to `cargo build`, which would reject `--bins`/`--tests`/
+/// `--examples` outright for a package with none of that kind ("no X
+/// targets found") instead of the no_std violation we're trying to report.
+/// `--all-targets` is always kept as-is: it's cargo's own "whatever is
+/// present" flag and never errors on an absent kind.
+///
+/// A plain `--lib` compile is also added whenever it's missing but the
+/// package does have a library target, even if the user only asked for e.g.
+/// `--tests`. That compile is the only one that's never a harness target
+/// (see `is_harness_target`), so it's what lets `cargo_command` tell a
+/// genuine `std` violation in the library apart from the harness's own
+/// unavoidable link -- without it, a unit-test-only crate would only ever
+/// produce harness invocations, and a violation in the library itself would
+/// be indistinguishable from the harness's own expected `std` link.
+fn package_target_kinds<'a>(pkg: &cargo_metadata::Package, requested_kinds: &[&'a str]) -> Vec<&'a str> {
+    let mut kinds: Vec<&str> = requested_kinds
+        .iter()
+        .copied()
+        .filter(|&flag| match flag {
+            "--lib" => has_lib_target(pkg),
+            "--bins" => pkg.targets.iter().any(|t| t.kind.iter().any(|k| k == "bin")),
+            "--examples" => pkg.targets.iter().any(|t| t.kind.iter().any(|k| k == "example")),
+            "--tests" => pkg.targets.iter().any(|t| t.kind.iter().any(|k| k == "test")),
+            _ => true, // --all-targets (or anything else): never filtered
+        })
+        .collect();
+    if has_lib_target(pkg) && !kinds.iter().any(|&k| k == "--lib" || k == "--all-targets") {
+        kinds.push("--lib");
+    }
+    kinds
+}
+
+/// Work out which workspace packages should be checked, honoring `-p`/
+/// `--package`, `--workspace` and `--exclude` the same way `cargo build`
+/// does, but defaulting to just the package selected by the current
+/// directory (cargo's own default) rather than the whole workspace.
+fn selected_packages(cargo_meta: &cargo_metadata::Metadata, args: &[String]) -> Result<Vec<String>> {
+    let exclude: HashSet<String> = get_args_equals("--exclude", args).into_iter().collect();
+    let explicit: Vec<String> = get_args_equals("-p", args)
+        .into_iter()
+        .chain(get_args_equals("--package", args))
+        .collect();
+
+    let names: Vec<String> = if has_flag("--workspace", args) || has_flag("--all", args) {
+        cargo_meta
+            .workspace_members
+            .iter()
+            .filter_map(|id| cargo_meta.packages.iter().find(|p| &p.id == id))
+            .map(|p| p.name.clone())
+            .filter(|name| !exclude.contains(name))
+            .collect()
+    } else if !explicit.is_empty() {
+        explicit
+    } else {
+        let root_id = cargo_meta
+            .resolve
+            .as_ref()
+            .and_then(|resolve| resolve.root.as_ref())
+            .ok_or_else(|| {
+                anyhow!("no default package found; pass -p/--package or --workspace")
+            })?;
+        let pkg = cargo_meta
+            .packages
+            .iter()
+            .find(|p| &p.id == root_id)
+            .ok_or_else(|| anyhow!("package metadata missing for the default package"))?;
+        vec![pkg.name.clone()]
+    };
+
+    ensure!(!names.is_empty(), "no packages selected to check");
+    Ok(names)
+}
+
 fn cargo_bar_style() -> ProgressStyle {
     ProgressStyle::default_bar()
         .template("{prefix:>12.bold.cyan} [{bar:60}] {pos}/{len}: {msg}")
@@ -74,18 +413,24 @@ fn cargo_bar_style() -> ProgressStyle {
 
 fn build_sysroot(
     host_target: &str,
+    target: &str,
     nostd_target: &str,
     src_sysroot: &Path,
     dst_sysroot: &Path,
     target_path: &Path,
+    block: &BlockList,
 ) -> Result<()> {
     eprintln!(
         "{:>12} #![no_std] sysroot",
         console::style("Creating").bold().green()
     );
 
-    // Root Paths.
-    let src_root = src_sysroot.join("lib/rustlib").join(host_target);
+    // Root Paths. Host tool libs (needed by build scripts and proc-macros,
+    // which always run on the host) come from `host_target`; the libs that
+    // make up the `#![no_std]` sysroot come from `target`, which may be a
+    // cross target with its own pre-built `rust-std` component.
+    let host_root = src_sysroot.join("lib/rustlib").join(host_target);
+    let target_root = src_sysroot.join("lib/rustlib").join(target);
     let dst_root = dst_sysroot.join("lib/rustlib");
     let dst_host_root = dst_root.join(host_target);
     let dst_nostd_root = dst_root.join(nostd_target);
@@ -93,8 +438,9 @@ fn build_sysroot(
     // List of source/dst entries to copy.
     let mut to_copy = <Vec<(PathBuf, PathBuf)>>::new();
 
-    // Copy over `bin` entries.
-    let src_bin = src_root.join("bin");
+    // Copy over `bin` entries (host tools only; cross targets don't ship
+    // binaries).
+    let src_bin = host_root.join("bin");
     let dst_host_bin = dst_host_root.join("bin");
     for entry in WalkDir::new(&src_bin) {
         let entry = entry?;
@@ -104,11 +450,23 @@ fn build_sysroot(
         }
     }
 
-    // Copy over `lib` entries.
-    let src_lib = src_root.join("lib");
+    // Copy over the host's `lib` entries, untouched, so build scripts and
+    // proc-macros still have `libstd` to link against.
+    let src_host_lib = host_root.join("lib");
     let dst_host_lib = dst_host_root.join("lib");
+    for entry in WalkDir::new(&src_host_lib) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let suffix = entry.path().strip_prefix(&src_host_lib).unwrap();
+            to_copy.push((entry.path().to_owned(), dst_host_lib.join(suffix)));
+        }
+    }
+
+    // Copy over `target`'s `lib` entries, stripping blocked libs, into the
+    // `#![no_std]` sysroot.
+    let src_target_lib = target_root.join("lib");
     let dst_nostd_lib = dst_nostd_root.join("lib");
-    for entry in WalkDir::new(&src_lib) {
+    for entry in WalkDir::new(&src_target_lib) {
         let entry = entry?;
         if entry.file_type().is_file() {
             let lib_name = entry
@@ -118,11 +476,8 @@ fn build_sysroot(
                 .split(|c| c == '-' || c == '.')
                 .next()
                 .unwrap();
-            let suffix = entry.path().strip_prefix(&src_lib).unwrap();
-            to_copy.push((entry.path().to_owned(), dst_host_lib.join(suffix)));
-
-            // Copy everything but `libstd` to `dst_nostd_lib`.
-            if lib_name != "libstd" {
+            if !block.blocks(lib_name) {
+                let suffix = entry.path().strip_prefix(&src_target_lib).unwrap();
                 to_copy.push((entry.path().to_owned(), dst_nostd_lib.join(suffix)));
             }
         }
@@ -141,13 +496,159 @@ fn build_sysroot(
     }
     pb.finish_and_clear();
 
-    let target_json = get_target_spec_json()?;
+    let target_json = get_target_spec_json(target)?;
     fs::write(target_path, target_json)?;
 
     Ok(())
 }
 
+/// Names of all the files under `<target>/lib` that the fake sysroot's
+/// target libs are copied from (blocked libs included; the blocklist is keyed
+/// separately), used to key the cache below. Walked separately from
+/// `build_sysroot` so we can compute the cache key before deciding whether a
+/// build is even necessary.
+fn sysroot_lib_names(src_sysroot: &Path, target: &str) -> Result<Vec<String>> {
+    let src_lib = src_sysroot.join("lib/rustlib").join(target).join("lib");
+    let mut names = Vec::new();
+    for entry in WalkDir::new(&src_lib) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Compute a cache key for a materialized `#![no_std]` sysroot from
+/// everything that can change its contents: the rustc sysroot we're copying
+/// out of, the rustc commit it was built from, the fake target triple, and
+/// the set of libs being copied.
+fn sysroot_cache_key(
+    src_sysroot: &Path,
+    commit_hash: Option<&str>,
+    nostd_target: &str,
+    lib_names: &[String],
+    block: &BlockList,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    src_sysroot.hash(&mut hasher);
+    commit_hash.unwrap_or("").hash(&mut hasher);
+    nostd_target.hash(&mut hasher);
+    lib_names.hash(&mut hasher);
+    let mut blocked: Vec<&String> = block.names.iter().collect();
+    blocked.sort();
+    blocked.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Reuse a previously-built `#![no_std]` sysroot for `key` if one exists,
+/// otherwise build a fresh one and publish it for next time.
+///
+/// Concurrent invocations are serialized with an advisory lock on a
+/// `.cargo-lock` file scoped to `key` (like cargo-xbuild does), and the
+/// freshly built sysroot is assembled in a temp directory and `rename`d into
+/// place so readers never observe a half-copied tree.
+fn materialize_sysroot(
+    cache_root: &Path,
+    key: &str,
+    host_target: &str,
+    target: &str,
+    nostd_target: &str,
+    src_sysroot: &Path,
+    block: &BlockList,
+) -> Result<(PathBuf, PathBuf)> {
+    fs::create_dir_all(cache_root)?;
+
+    let dst = cache_root.join(key);
+    let target_path = dst.join(format!("{}.json", nostd_target));
+    let ok_marker = dst.join(".nostd-sysroot-ok");
+    if ok_marker.is_file() {
+        return Ok((dst, target_path));
+    }
+
+    let lock_path = cache_root.join(format!("{}.cargo-lock", key));
+    let lock_file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&lock_path)?;
+    lock_file.lock_exclusive()?;
+
+    // Another process may have finished the build while we were waiting on
+    // the lock.
+    if ok_marker.is_file() {
+        return Ok((dst, target_path));
+    }
+
+    let tmp = cache_root.join(format!(".{}.tmp", key));
+    let _ = fs::remove_dir_all(&tmp);
+    let tmp_target_path = tmp.join(format!("{}.json", nostd_target));
+    build_sysroot(
+        host_target,
+        target,
+        nostd_target,
+        src_sysroot,
+        &tmp,
+        &tmp_target_path,
+        block,
+    )?;
+    fs::write(tmp.join(".nostd-sysroot-ok"), key)?;
+
+    let _ = fs::remove_dir_all(&dst);
+    fs::rename(&tmp, &dst)?;
+
+    Ok((dst, target_path))
+}
+
 fn cargo_command(args: Vec<String>) -> Result<()> {
+    if has_flag("-h", &args) || has_flag("--help", &args) {
+        println!(
+            "\
+Wrapper for `cargo build` that prevents linking against libstd.
+
+USAGE:
+    cargo no-std-check [OPTIONS]
+
+OPTIONS:
+    -h, --help              Prints help information and exit
+    --version               Prints version information and exit
+    --block <crate>         Also exclude <crate> from the generated sysroot
+                            (repeatable). Accepts a bare crate name, or a
+                            cfg-style predicate such as 'all(alloc, test)',
+                            evaluated the way `cfg(...)` target predicates
+                            are: that example blocks `alloc`, but only when
+                            `test` is also being blocked.
+    --core-only             Shorthand for --block alloc --block test
+                            --block proc_macro, to check a crate against
+                            `core` alone.
+    -p, --package <name>    Check <name> instead of the default package
+                            (repeatable).
+    --workspace, --all      Check every workspace member with a library
+                            target.
+    --exclude <name>        With --workspace, skip <name> (repeatable).
+    --lib                   Check the library target (default).
+    --bins                  Also check binary targets.
+    --examples              Also check example targets.
+    --tests                 Also check test targets. Since cargo's own test
+                            harness always links `std` to run itself, a
+                            missing-`std` failure confined to harness
+                            targets is reported separately and doesn't fail
+                            the check.
+    --all-targets           Passed through as cargo's own --all-targets:
+                            library, binaries, examples, tests and benches.
+
+    Any additional options are directly passed to `cargo build` (see `cargo
+    build --help` for possible options).
+"
+        );
+        return Ok(());
+    }
+
+    if has_flag("--version", &args) {
+        println!(concat!("cargo-no-std-check ", env!("CARGO_PKG_VERSION")));
+        return Ok(());
+    }
+
     let current_exe = env::current_exe()?;
 
     let rustc_meta = rustc_version::version_meta()?;
@@ -163,23 +664,34 @@ fn cargo_command(args: Vec<String>) -> Result<()> {
     let cargo_meta = meta_cmd.exec()?;
 
     let host_target = &rustc_meta.host;
-    let nostd_target = format!("{}-nostd", host_target);
+    // The target actually being checked, which may be a cross target with its
+    // own pre-built `rust-std` component, distinct from the host we run on.
+    let target = get_arg_equals("--target", &args).unwrap_or_else(|| host_target.clone());
+    let nostd_target = format!("{}-nostd", target);
 
     // XXX: Allow configuring the path?
-    let nostd_sysroot = cargo_meta.workspace_root.join("target/nostd_sysroot");
-    let target_path = nostd_sysroot.join(format!("{}.json", nostd_target));
+    let cache_root = cargo_meta.workspace_root.join("target/nostd_sysroot");
 
-    let _ = fs::remove_dir_all(&nostd_sysroot);
+    let block = BlockList::from_args(&args)?;
 
-    // Build our new sysroot.
-    // FIXME: Support caching?
     let sysroot = get_sysroot()?;
-    build_sysroot(
-        &host_target,
+    ensure_target_installed(&sysroot, &target)?;
+    let lib_names = sysroot_lib_names(&sysroot, &target)?;
+    let key = sysroot_cache_key(
+        &sysroot,
+        rustc_meta.commit_hash.as_deref(),
+        &nostd_target,
+        &lib_names,
+        &block,
+    );
+    let (nostd_sysroot, target_path) = materialize_sysroot(
+        cache_root.as_std_path(),
+        &key,
+        host_target,
+        &target,
         &nostd_target,
         &sysroot,
-        &nostd_sysroot,
-        &target_path,
+        &block,
     )?;
 
     eprintln!(
@@ -189,21 +701,155 @@ fn cargo_command(args: Vec<String>) -> Result<()> {
         nostd_sysroot.display(),
     );
 
-    // Run cargo build
-    let status = cargo()
-        .arg("build")
-        .arg("--target")
-        .arg("no_std-fake-target")
-        .args(&args)
-        .env("RUSTC_WRAPPER", &current_exe)
-        .env("CARGO_NOSTD_CHECK", &nostd_sysroot)
-        .env("CARGO_NOSTD_TARGET", &host_target)
-        .status()?;
-    ensure!(status.success(), "cargo build exited with failure");
+    // By default we only check the library target, same as `cargo check
+    // --lib` would. Passing one of these selects non-library targets too, so
+    // users can confirm their examples/tests/binaries also stay `#![no_std]`
+    // -- note those always link a runtime of their own (see `is_harness_target`
+    // in `rustc_wrapper`), so a `std` failure there isn't necessarily a
+    // violation in the checked crate.
+    const TARGET_KIND_FLAGS: &[&str] = &["--lib", "--bins", "--examples", "--tests", "--all-targets"];
+    let requested_kinds: Vec<&str> = TARGET_KIND_FLAGS
+        .iter()
+        .copied()
+        .filter(|&flag| has_flag(flag, &args))
+        .collect();
+    let target_kinds: Vec<&str> = if requested_kinds.is_empty() {
+        vec!["--lib"]
+    } else {
+        requested_kinds
+    };
+
+    // Figure out which workspace packages to check, then strip all package
+    // selection and target-kind flags from the args we forward, since we
+    // issue one `cargo build -p <name> <target-kind>` per selected package
+    // below rather than a single invocation covering cargo's own default
+    // selection.
+    let packages = selected_packages(&cargo_meta, &args)?;
+    let forward_args = without_arg_equals("--target", &args);
+    let forward_args = without_arg_equals("--block", &forward_args);
+    let forward_args = without_flag("--core-only", &forward_args);
+    let forward_args = without_arg_equals("-p", &forward_args);
+    let forward_args = without_arg_equals("--package", &forward_args);
+    let forward_args = without_arg_equals("--exclude", &forward_args);
+    let forward_args = without_flag("--workspace", &forward_args);
+    let forward_args = without_flag("--all", &forward_args);
+    let forward_args = TARGET_KIND_FLAGS
+        .iter()
+        .fold(forward_args, |args, flag| without_flag(flag, &args));
+
+    // Run cargo build per selected package, stripping the user's real
+    // `--target` (if any) in favor of our fake one, which `rustc_wrapper`
+    // rewrites back to `target` for every invocation cargo passes it to.
+    let violations_log = cache_root.as_std_path().join(".std-check-log");
+    let mut failed = Vec::new();
+    let mut harness_only = Vec::new();
+    for name in &packages {
+        let pkg = cargo_meta
+            .packages
+            .iter()
+            .find(|p| &p.name == name)
+            .ok_or_else(|| anyhow!("package `{}` not found in workspace metadata", name))?;
+        let pkg_kinds = package_target_kinds(pkg, &target_kinds);
+        if pkg_kinds.is_empty() {
+            eprintln!(
+                "{:>12} {} (no target matching {})",
+                console::style("Skipping").bold().yellow(),
+                name,
+                target_kinds.join(" "),
+            );
+            continue;
+        }
+
+        eprintln!(
+            "{:>12} {}",
+            console::style("Checking").bold().green(),
+            name,
+        );
+        let _ = fs::remove_file(&violations_log);
+        let output = cargo()
+            .arg("build")
+            .args(&pkg_kinds)
+            .arg("--target")
+            .arg("no_std-fake-target")
+            .arg("-p")
+            .arg(name)
+            .args(&forward_args)
+            .env("RUSTC_WRAPPER", &current_exe)
+            .env("CARGO_NOSTD_CHECK", &nostd_sysroot)
+            .env("CARGO_NOSTD_TARGET", &target)
+            .env("CARGO_NOSTD_VIOLATIONS_LOG", &violations_log)
+            .stderr(Stdio::piped())
+            .output()?;
+        io::stderr().write_all(&output.stderr)?;
+
+        if output.status.success() {
+            continue;
+        }
+        // `rustc_wrapper` logs one line per invocation that hit a missing-
+        // `std` error, recording whether *that specific invocation* was a
+        // harness target (see `is_harness_target`). Only count this package
+        // as a violation if some non-harness invocation (the library itself,
+        // guaranteed present by `package_target_kinds`) hit it -- a build
+        // failure with no such line logged at all is some other, unrelated
+        // compile error and still counts as a real failure.
+        match classify_failure(&violations_log) {
+            Failure::HarnessOnly => harness_only.push(name.clone()),
+            Failure::Violation | Failure::Other => failed.push(name.clone()),
+        }
+    }
+
+    if !harness_only.is_empty() {
+        eprintln!(
+            "{:>12} {} (only harness-induced `std` links failed, not counted)",
+            console::style("Expected").bold().yellow(),
+            harness_only.join(", "),
+        );
+    }
+
+    ensure!(
+        failed.is_empty(),
+        "{} of {} crate(s) link against libstd: {}",
+        failed.len(),
+        packages.len(),
+        failed.join(", ")
+    );
 
     Ok(())
 }
 
+/// How a failed package build should be reported, based on the per-
+/// invocation log `rustc_wrapper` writes to `CARGO_NOSTD_VIOLATIONS_LOG`.
+enum Failure {
+    /// Every missing-`std` error came from a harness invocation (see
+    /// `is_harness_target`) -- the checked crate itself stayed `#![no_std]`.
+    HarnessOnly,
+    /// At least one missing-`std` error came from a non-harness invocation:
+    /// a genuine violation in the checked crate.
+    Violation,
+    /// The build failed for some other reason; no missing-`std` error was
+    /// logged at all.
+    Other,
+}
+
+/// Classify a package's build failure from the violations log left behind
+/// by `rustc_wrapper`, which records one `<crate>\t<is_harness>` line per
+/// rustc invocation that hit a missing-`std` error.
+fn classify_failure(log_path: &Path) -> Failure {
+    let log = fs::read_to_string(log_path).unwrap_or_default();
+    let mut saw_any = false;
+    for line in log.lines() {
+        saw_any = true;
+        if line.ends_with("\tfalse") {
+            return Failure::Violation;
+        }
+    }
+    if saw_any {
+        Failure::HarnessOnly
+    } else {
+        Failure::Other
+    }
+}
+
 fn rustc_wrapper(mut args: Vec<String>, sysroot: String) -> Result<()> {
     ensure!(!args.is_empty(), "expected rustc argument");
 
@@ -245,17 +891,101 @@ fn rustc_wrapper(mut args: Vec<String>, sysroot: String) -> Result<()> {
 
     tracing::info!("{}", args.join(" "));
 
-    let status = Command::new(&args[0]).args(&args[1..]).status()?;
+    if !found_target {
+        let status = Command::new(&args[0]).args(&args[1..]).status()?;
+        return match status.code() {
+            Some(code) => std::process::exit(code),
+            None => bail!("rustc exited with signal"),
+        };
+    }
+
+    // Targets compiled with a test harness (unit/integration tests, which
+    // rustc builds with `--test`) or as a `bin` (plain binaries, and
+    // `examples` by default) always link `std` themselves to drive the
+    // harness or `main`, independent of whatever the checked crate uses.
+    // Capture stderr for every invocation against our libstd-free sysroot, so
+    // a "can't find crate for `std`" failure can be attributed to whether
+    // *this* invocation was one of those -- `cargo_command` reads the log
+    // written below to tell an expected harness link apart from a genuine
+    // violation in the checked crate, which a pooled, whole-package view of
+    // stderr can't do (both produce the identical diagnostic).
+    let harness = is_harness_target(&args);
+    let output = Command::new(&args[0])
+        .args(&args[1..])
+        .stderr(Stdio::piped())
+        .output()?;
+    io::stderr().write_all(&output.stderr)?;
+    if !output.status.success() && stderr_mentions_missing_std(&output.stderr) {
+        if harness {
+            eprintln!(
+                "{:>12} {} links `std` via cargo's own bin/test harness, \
+                 independent of the checked crate; pass `--lib` (the default) \
+                 to check only the library target",
+                console::style("Note").bold().yellow(),
+                crate_name(&args).unwrap_or("this target"),
+            );
+        }
+        if let Ok(log_path) = env::var("CARGO_NOSTD_VIOLATIONS_LOG") {
+            if let Ok(mut log) = fs::OpenOptions::new().create(true).append(true).open(log_path) {
+                let _ = writeln!(log, "{}\t{}", crate_name(&args).unwrap_or("?"), harness);
+            }
+        }
+    }
 
-    match status.code() {
+    match output.status.code() {
         Some(code) => std::process::exit(code),
         None => bail!("rustc exited with signal"),
     }
 }
 
+/// Does this rustc invocation build a target that links its own runtime
+/// (a test harness, or a `bin`-like crate) regardless of what the checked
+/// crate imports? Those always pull in `std`, so a missing-`std` error there
+/// doesn't indicate a violation the way one in a `lib`/`rlib`/`proc-macro`
+/// crate would.
+fn is_harness_target(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--test")
+        || args
+            .windows(2)
+            .any(|pair| pair[0] == "--crate-type" && pair[1] == "bin")
+        || args.iter().any(|arg| arg == "--crate-type=bin")
+}
+
+/// The `--crate-name` passed to this rustc invocation, if any, for
+/// annotating harness-related notes.
+fn crate_name(args: &[String]) -> Option<&str> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--crate-name" {
+            return iter.next().map(|s| s.as_str());
+        }
+        if let Some(name) = arg.strip_prefix("--crate-name=") {
+            return Some(name);
+        }
+    }
+    None
+}
+
+fn stderr_mentions_missing_std(stderr: &[u8]) -> bool {
+    String::from_utf8_lossy(stderr).contains("can't find crate for `std`")
+}
+
+/// Cargo passes the subcommand name as the first argument when invoking us
+/// as `cargo no-std-check`; drop it if present, so the rest of argument
+/// parsing doesn't have to special-case it.
+fn strip_subcommand_name(mut args: Vec<String>) -> Vec<String> {
+    if matches!(args.first(), Some(first) if first == "no-std-check") {
+        args.remove(0);
+    }
+    args
+}
+
+/// Entry point for both the `cargo-no-std-check` binary and, via
+/// `RUSTC_WRAPPER`, every `rustc` invocation it spawns (distinguished by the
+/// presence of `CARGO_NOSTD_CHECK` in the environment).
 pub fn run(args: Vec<String>) -> Result<()> {
     match env::var("CARGO_NOSTD_CHECK").ok() {
         Some(sysroot) => rustc_wrapper(args, sysroot),
-        None => cargo_command(args),
+        None => cargo_command(strip_subcommand_name(args)),
     }
 }
@@ -0,0 +1,11 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+pub fn make() -> Vec<u8> {
+    let mut v = Vec::new();
+    v.push(1);
+    v
+}
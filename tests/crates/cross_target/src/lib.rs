@@ -0,0 +1,5 @@
+#![no_std]
+
+pub fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
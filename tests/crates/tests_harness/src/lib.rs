@@ -0,0 +1,15 @@
+#![no_std]
+
+pub fn triple(x: u32) -> u32 {
+    x * 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_triples() {
+        assert_eq!(triple(2), 6);
+    }
+}
@@ -0,0 +1,5 @@
+#![no_std]
+
+pub fn double(x: u32) -> u32 {
+    x * 2
+}